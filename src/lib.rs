@@ -1,74 +1,374 @@
-use std::fs::{self, File};
-use std::io::{ErrorKind, Read};
-use std::path::Path;
+#![forbid(unsafe_code)]
+#![deny(clippy::all, clippy::pedantic)]
+#![warn(clippy::nursery)]
+#![allow(clippy::missing_errors_doc)]
 
-use colored::*;
+//! Library API for checking and updating file checksums.
+//!
+//! `check` and `check_sfv` return [`FileReport`]s describing what happened
+//! to each file instead of printing anything themselves, so the engine can
+//! be embedded in other programs; the `crccheck` binary is just one
+//! consumer that adds coloring, sorting and a progress indicator on top.
+
+mod rlimit;
+pub mod sfv;
+
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use async_stream::stream;
+use clap::ArgEnum;
 use crc32fast::Hasher;
-use crossbeam_utils::sync::WaitGroup;
-use failure::{err_msg, Error};
-use threadpool::ThreadPool;
+use futures_util::stream::{Stream, StreamExt, TryStreamExt};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs::{self, DirEntry, File},
+    io::{AsyncReadExt, ErrorKind},
+};
 
-pub fn check<P: AsRef<Path>>(files: &Vec<P>, update: bool, add: bool) -> Result<(), Error> {
-    let pool = ThreadPool::new(num_cpus::get() * 4);
-    let wg = WaitGroup::new();
+/// Hash algorithm used to compute and compare checksums
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum Algorithm {
+    Crc32,
+    Crc32c,
+    Md5,
+    Sha1,
+    Sha256,
+    Blake3,
+}
 
-    for file in files {
-        let file = file.as_ref();
-        if file.is_dir() {
-            continue;
+impl Algorithm {
+    /// The width, in hex digits, of a checksum produced by this algorithm.
+    const fn hex_width(self) -> usize {
+        match self {
+            Self::Crc32 | Self::Crc32c => 8,
+            Self::Md5 => 32,
+            Self::Sha1 => 40,
+            Self::Sha256 | Self::Blake3 => 64,
         }
+    }
+}
+
+/// Outcome of checking a single file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Mismatch,
+    Updated,
+    Skipped,
+    Missing,
+    Added,
+}
+
+/// The result of checking a single file, whether its checksum came from its
+/// file name or from an SFV manifest entry.
+#[derive(Debug, Clone)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub expected: Option<Vec<u8>>,
+    pub actual: Option<Vec<u8>>,
+    pub status: Status,
+}
+
+/// Check every file under `dir` against a checksum embedded in its file name.
+///
+/// Optionally recurses into subdirectories. `on_progress` is called after
+/// each file completes with `(done, total)`, so callers that don't care
+/// about progress can pass `|_, _| {}`.
+pub async fn check<P, F>(
+    dir: P,
+    update: bool,
+    recursive: bool,
+    algo: Algorithm,
+    mut on_progress: F,
+) -> Result<Vec<FileReport>>
+where
+    P: AsRef<Path> + Send,
+    F: FnMut(usize, usize) + Send,
+{
+    rlimit::raise_nofile_limit();
+
+    let entries = read_dir(dir, recursive).await?.collect::<Vec<_>>().await;
+
+    let mut files = Vec::new();
+    let mut reports = Vec::new();
+    for entry in entries {
+        // A directory we couldn't list or an entry we couldn't stat: no
+        // file to report on, just move past it.
+        let Ok(entry) = entry else { continue };
+
+        match entry.metadata().await {
+            Ok(metadata) if metadata.is_file() => files.push(entry.path()),
+            Ok(_) => {}
+            Err(_) => reports.push(FileReport {
+                path: entry.path(),
+                expected: None,
+                actual: None,
+                status: Status::Missing,
+            }),
+        }
+    }
+
+    let total = files.len();
+    let mut done = 0;
+
+    let checked = futures_util::stream::iter(files)
+        .map(|file| check_file(file, update, algo))
+        .buffer_unordered(num_cpus::get() * 2)
+        .inspect(|_| {
+            done += 1;
+            on_progress(done, total);
+        })
+        .collect::<Vec<_>>()
+        .await;
+
+    reports.extend(checked);
+    Ok(reports)
+}
+
+/// Verify the files listed in the SFV manifest at `sfv` against the
+/// checksums recorded there, reporting Ok/Mismatch/Missing for each.
+///
+/// With `update` a mismatched checksum is corrected in the manifest; with
+/// `add` any file in `dir` that isn't listed yet is hashed and appended.
+/// Either flag triggers a rewrite of the manifest once all files have been
+/// checked. A file with a non-UTF-8 name can't be recorded in the manifest,
+/// so it's reported as [`Status::Missing`] instead of being added.
+pub async fn check_sfv<P: AsRef<Path> + Send>(
+    dir: P,
+    sfv: PathBuf,
+    update: bool,
+    add: bool,
+    algo: Algorithm,
+) -> Result<Vec<FileReport>> {
+    rlimit::raise_nofile_limit();
+
+    let dir = dir.as_ref();
+    let mut entries = sfv::parse(&sfv).await?;
+    let mut known = entries
+        .iter()
+        .map(|e| e.name.clone())
+        .collect::<HashSet<_>>();
+    let mut reports = Vec::with_capacity(entries.len());
+
+    for entry in &mut entries {
+        let path = dir.join(&entry.name);
+
+        let report = match calculate_hash(&path, algo).await {
+            Ok(actual) if actual == entry.hash => FileReport {
+                path,
+                expected: Some(entry.hash.clone()),
+                actual: Some(actual),
+                status: Status::Ok,
+            },
+            Ok(actual) if update => {
+                let report = FileReport {
+                    path,
+                    expected: Some(entry.hash.clone()),
+                    actual: Some(actual.clone()),
+                    status: Status::Updated,
+                };
+                entry.hash = actual;
+                report
+            }
+            Ok(actual) => FileReport {
+                path,
+                expected: Some(entry.hash.clone()),
+                actual: Some(actual),
+                status: Status::Mismatch,
+            },
+            Err(_) => FileReport {
+                path,
+                expected: Some(entry.hash.clone()),
+                actual: None,
+                status: Status::Missing,
+            },
+        };
+        reports.push(report);
+    }
 
-        let wg = wg.clone();
-        let file = file.to_path_buf();
-        pool.execute(move || {
-            check_crc(file.as_path(), update, add).unwrap();
-            drop(wg);
+    if add {
+        // Resolved once up front, so each scanned entry only needs its own
+        // path canonicalized to be compared against it. A relative
+        // `--sfv` path and a `dir.join(name)`-built path don't render the
+        // same even when they're the same file, so only a canonical
+        // comparison reliably excludes the manifest from its own listing.
+        let sfv_canonical = fs::canonicalize(&sfv).await.ok();
+
+        let files = read_dir(dir, false).await?.collect::<Vec<_>>().await;
+        let temp = futures_util::stream::iter(files).try_filter_map(|file: DirEntry| async move {
+            if file.metadata().await?.is_file() {
+                Ok(Some(file))
+            } else {
+                Ok(None)
+            }
         });
+        let mut files = Box::pin(temp);
+
+        while let Some(file) = files.try_next().await? {
+            if sfv_canonical.is_some() && fs::canonicalize(file.path()).await.ok() == sfv_canonical
+            {
+                continue;
+            }
+
+            let Some(name) = file.file_name().to_str().map(str::to_owned) else {
+                reports.push(FileReport {
+                    path: file.path(),
+                    expected: None,
+                    actual: None,
+                    status: Status::Missing,
+                });
+                continue;
+            };
+            if !known.insert(name.clone()) {
+                continue;
+            }
+
+            let actual = calculate_hash(&file.path(), algo).await?;
+            entries.push(sfv::Entry {
+                name,
+                hash: actual.clone(),
+            });
+            reports.push(FileReport {
+                path: file.path(),
+                expected: None,
+                actual: Some(actual),
+                status: Status::Added,
+            });
+        }
     }
 
-    wg.wait();
-    Ok(())
+    if update || add {
+        sfv::write(&sfv, &entries).await?;
+    }
+
+    Ok(reports)
 }
 
-fn check_crc(file: &Path, update: bool, add: bool) -> Result<(), Error> {
-    let name = file.file_name().unwrap().to_str().unwrap();
-    let hash_bytes = extract_hash(name)?;
-    let calc_bytes = match calculate_hash(file) {
-        Ok(v) => v,
-        Err(e) => return Err(e),
+/// Walk `dir`, yielding every entry found.
+///
+/// When `recursive` is set, subdirectories are not descended into directly;
+/// instead their already-opened handles are pushed onto a work queue and
+/// drained in turn, so the traversal stays flat (no recursive `async fn`
+/// calls) and its depth is bounded only by available memory, not stack
+/// space. Reading the root directory fails fast, same as before recursion
+/// existed, but a problem further down the tree (a permission-denied
+/// subdirectory, a file removed mid-walk) only yields an `Err` item for
+/// that entry and the walk continues — one bad branch shouldn't discard
+/// every file already discovered.
+async fn read_dir(
+    dir: impl AsRef<Path> + Send,
+    recursive: bool,
+) -> Result<impl Stream<Item = Result<DirEntry>>> {
+    let root = fs::read_dir(dir.as_ref()).await?;
+
+    Ok(stream! {
+        let mut queue = VecDeque::new();
+        queue.push_back(root);
+
+        while let Some(mut entries) = queue.pop_front() {
+            loop {
+                let entry = match entries.next_entry().await {
+                    Ok(Some(entry)) => entry,
+                    Ok(None) => break,
+                    Err(e) => {
+                        yield Err(e.into());
+                        break;
+                    }
+                };
+
+                let is_dir = match entry.metadata().await {
+                    Ok(metadata) => metadata.is_dir(),
+                    Err(e) => {
+                        yield Err(e.into());
+                        continue;
+                    }
+                };
+
+                if recursive && is_dir {
+                    match fs::read_dir(entry.path()).await {
+                        Ok(sub) => queue.push_back(sub),
+                        Err(e) => yield Err(e.into()),
+                    }
+                } else {
+                    yield Ok(entry);
+                }
+            }
+        }
+    })
+}
+
+/// Check a single file, never failing outright: anything that goes wrong
+/// along the way (a non-UTF-8 or unparsable file name, the file vanishing
+/// before it can be read, a failed rename) is folded into the returned
+/// report's status instead of aborting the batch it's part of.
+async fn check_file(path: PathBuf, update: bool, algo: Algorithm) -> FileReport {
+    let Some(name) = path.file_name().and_then(std::ffi::OsStr::to_str) else {
+        return FileReport {
+            path,
+            expected: None,
+            actual: None,
+            status: Status::Missing,
+        };
     };
 
-    let result = match hash_bytes {
-        None => {
-            if add {
-                add_file_hash(file, calc_bytes)?;
-                "ADDED".blue()
-            } else {
-                "SKIPPED".magenta()
+    let expected = match extract_hash(name, algo) {
+        Ok(Some(v)) => v,
+        Ok(None) => {
+            return FileReport {
+                path,
+                expected: None,
+                actual: None,
+                status: Status::Skipped,
             }
         }
-        Some(hash_bytes) => {
-            if hash_bytes == calc_bytes {
-                "OK".green()
-            } else if update {
-                update_file_hash(file, hash_bytes, calc_bytes)?;
-                "UPDATED".yellow()
-            } else {
-                "MISMATCH".red()
+        Err(_) => {
+            return FileReport {
+                path,
+                expected: None,
+                actual: None,
+                status: Status::Missing,
             }
         }
     };
 
-    println!("{:>8} - {}", result, name);
-    Ok(())
+    let Ok(actual) = calculate_hash(&path, algo).await else {
+        return FileReport {
+            path,
+            expected: Some(expected),
+            actual: None,
+            status: Status::Missing,
+        };
+    };
+
+    let status = if expected == actual {
+        Status::Ok
+    } else if update {
+        match rename_file(&path, &expected, &actual).await {
+            Ok(()) => Status::Updated,
+            Err(_) => Status::Mismatch,
+        }
+    } else {
+        Status::Mismatch
+    };
+
+    FileReport {
+        path,
+        expected: Some(expected),
+        actual: Some(actual),
+        status,
+    }
 }
 
-fn extract_hash(name: &str) -> Result<Option<u32>, Error> {
-    let mut sub = &name[..];
+fn extract_hash(name: &str, algo: Algorithm) -> Result<Option<Vec<u8>>> {
+    let width = algo.hex_width();
+    let mut sub = name;
     while let Some((l, r)) = find_surrounded(sub, '[', ']') {
         let hex = &sub[l + 1..r];
-        if is_u32_hex(hex) {
-            return Ok(Some(u32::from_str_radix(hex, 16)?));
+        if is_hex_of_width(hex, width) {
+            return Ok(Some(hex::decode(hex)?));
         }
         sub = &sub[..l];
     }
@@ -86,45 +386,75 @@ fn find_surrounded(text: &str, left: char, right: char) -> Option<(usize, usize)
 }
 
 #[inline]
-fn is_u32_hex(text: &str) -> bool {
-    text.len() == 8 && text.chars().all(|c| "0123456789abcdefABCDEF".contains(c))
+fn is_hex_of_width(text: &str, width: usize) -> bool {
+    text.len() == width && text.chars().all(|c| c.is_ascii_hexdigit())
 }
 
-fn calculate_hash(file: &Path) -> Result<u32, Error> {
-    let mut file = File::open(file)?;
-    let mut buf = [0u8; 8192];
-    let mut hasher = Hasher::new();
+/// Read `file` in chunks, feeding each chunk to `update`.
+async fn read_chunks(file: &PathBuf, mut update: impl FnMut(&[u8])) -> Result<()> {
+    let mut file = File::open(file).await?;
+    let mut buf = [0_u8; 8192];
 
     loop {
-        match file.read(&mut buf) {
-            Ok(0) => return Ok(hasher.finalize()),
-            Ok(len) => hasher.update(&buf[..len]),
-            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+        match file.read(&mut buf).await {
+            Ok(0) => return Ok(()),
+            Ok(len) => update(&buf[..len]),
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
             Err(e) => return Err(e.into()),
-        };
+        }
     }
 }
 
-fn add_file_hash(file: &Path, hash_bytes: u32) -> Result<(), Error> {
-    if let Some(name) = file.to_str() {
-        let mut name = name.to_owned();
-        if let Some(i) = name.rfind(".") {
-            name.insert_str(i, &format!("[{:08X}]", hash_bytes));
-            fs::rename(file, name)?;
-            return Ok(());
+async fn calculate_hash(file: &PathBuf, algo: Algorithm) -> Result<Vec<u8>> {
+    match algo {
+        Algorithm::Crc32 => {
+            let mut hasher = Hasher::new();
+            read_chunks(file, |chunk| hasher.update(chunk)).await?;
+            Ok(hasher.finalize().to_be_bytes().to_vec())
+        }
+        Algorithm::Crc32c => {
+            let mut crc = 0_u32;
+            read_chunks(file, |chunk| crc = crc32c::crc32c_append(crc, chunk)).await?;
+            Ok(crc.to_be_bytes().to_vec())
+        }
+        Algorithm::Md5 => {
+            let mut ctx = md5::Context::new();
+            read_chunks(file, |chunk| ctx.consume(chunk)).await?;
+            Ok(ctx.compute().to_vec())
+        }
+        Algorithm::Sha1 => {
+            let mut hasher = Sha1::new();
+            read_chunks(file, |chunk| hasher.update(chunk)).await?;
+            Ok(hasher.digest().bytes().to_vec())
+        }
+        Algorithm::Sha256 => {
+            let mut hasher = Sha256::new();
+            read_chunks(file, |chunk| hasher.update(chunk)).await?;
+            Ok(hasher.finalize().to_vec())
+        }
+        Algorithm::Blake3 => {
+            let mut hasher = blake3::Hasher::new();
+            read_chunks(file, |chunk| {
+                hasher.update(chunk);
+            })
+            .await?;
+            // Call the inherent `finalize`, not `digest::Digest::finalize`:
+            // blake3 implements that trait too, and with both in scope the
+            // trait method wins method resolution over the `&self` inherent
+            // one, returning a `GenericArray` instead of a `Hash`.
+            Ok(blake3::Hasher::finalize(&hasher).as_bytes().to_vec())
         }
     }
-    Err(err_msg("can't add hash to file name"))
 }
 
-fn update_file_hash(file: &Path, hash_bytes: u32, calc_bytes: u32) -> Result<(), Error> {
-    let crc_hash = format!("[{:08X}]", hash_bytes);
-    let crc_calc = format!("[{:08X}]", calc_bytes);
+async fn rename_file(file: &PathBuf, hash_bytes: &[u8], calc_bytes: &[u8]) -> Result<()> {
+    let crc_hash = format!("[{}]", hex::encode_upper(hash_bytes));
+    let crc_calc = format!("[{}]", hex::encode_upper(calc_bytes));
     let new_name = file
         .to_str()
-        .ok_or(err_msg("can't update hash of file"))?
+        .unwrap_or_default()
         .replace(&crc_hash, &crc_calc);
-    fs::rename(file, new_name)?;
+    fs::rename(file, new_name).await?;
     Ok(())
 }
 
@@ -142,9 +472,9 @@ mod tests {
         ];
 
         for (input, expect) in &cases {
-            let result = extract_hash(input);
-            if let Ok(Some(i)) = result {
-                assert_eq!(expect, &format!("{:08X}", i));
+            let result = extract_hash(input, Algorithm::Crc32);
+            if let Ok(Some(v)) = &result {
+                assert_eq!(expect, &hex::encode_upper(v));
             } else {
                 panic!("Expected {} but got {:?}", expect, result);
             }
@@ -162,10 +492,157 @@ mod tests {
         ];
 
         for input in &cases {
-            let result = extract_hash(input);
-            if let Ok(Some(i)) = result {
-                panic!("No valued expected but got {}", format!("{:08X}", i));
+            let result = extract_hash(input, Algorithm::Crc32);
+            if let Ok(Some(v)) = &result {
+                panic!("No value expected but got {}", hex::encode_upper(v));
             }
         }
     }
+
+    #[test]
+    fn hex_width_matches_each_algorithm() {
+        let cases = [
+            (Algorithm::Crc32, 8),
+            (Algorithm::Crc32c, 8),
+            (Algorithm::Md5, 32),
+            (Algorithm::Sha1, 40),
+            (Algorithm::Sha256, 64),
+            (Algorithm::Blake3, 64),
+        ];
+
+        for (algo, width) in cases {
+            assert_eq!(algo.hex_width(), width);
+        }
+    }
+
+    #[test]
+    fn extract_hash_respects_algorithm_width() {
+        // An 8-char bracketed value only counts as a checksum for the
+        // 8-digit algorithms; for a 32-digit one it's just bracketed text.
+        assert_eq!(
+            extract_hash("[aabbccdd]", Algorithm::Crc32c).unwrap(),
+            Some(hex::decode("aabbccdd").unwrap())
+        );
+        assert_eq!(extract_hash("[aabbccdd]", Algorithm::Md5).unwrap(), None);
+    }
+
+    async fn write_temp_file(name: &str, content: &[u8]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("crccheck_lib_test_{}_{name}", std::process::id()));
+        fs::write(&path, content).await.unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn calculate_hash_matches_known_vectors() {
+        let cases = [
+            (Algorithm::Crc32, "0d4a1185"),
+            (Algorithm::Crc32c, "c99465aa"),
+            (Algorithm::Md5, "5eb63bbbe01eeed093cb22bb8f5acdc3"),
+            (Algorithm::Sha1, "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed"),
+            (
+                Algorithm::Sha256,
+                "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9",
+            ),
+        ];
+
+        for (algo, expect) in cases {
+            let path = write_temp_file("known_vectors", b"hello world").await;
+            let actual = calculate_hash(&path, algo).await.unwrap();
+            fs::remove_file(&path).await.unwrap();
+
+            assert_eq!(hex::encode(actual), expect, "algorithm {algo:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn calculate_hash_blake3_is_deterministic_and_full_width() {
+        let path = write_temp_file("blake3", b"hello world").await;
+        let first = calculate_hash(&path, Algorithm::Blake3).await.unwrap();
+        let second = calculate_hash(&path, Algorithm::Blake3).await.unwrap();
+        fs::remove_file(&path).await.unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(first.len() * 2, Algorithm::Blake3.hex_width());
+    }
+
+    fn temp_sub_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("crccheck_lib_test_walk_{}_{name}", std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn read_dir_non_recursive_lists_only_the_top_level() {
+        let root = temp_sub_dir("non_recursive");
+        let nested = root.join("nested");
+        fs::create_dir_all(&nested).await.unwrap();
+        fs::write(root.join("top.txt"), b"ok").await.unwrap();
+        fs::write(nested.join("deep.txt"), b"ok").await.unwrap();
+
+        let entries = read_dir(&root, false).await.unwrap().collect::<Vec<_>>().await;
+        fs::remove_dir_all(&root).await.unwrap();
+
+        let mut names = entries
+            .into_iter()
+            .filter_map(Result::ok)
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+
+        assert_eq!(names, vec!["nested".to_owned(), "top.txt".to_owned()]);
+    }
+
+    #[tokio::test]
+    async fn read_dir_recursive_descends_into_subdirectories() {
+        let root = temp_sub_dir("recursive");
+        let nested = root.join("nested");
+        fs::create_dir_all(&nested).await.unwrap();
+        fs::write(root.join("top.txt"), b"ok").await.unwrap();
+        fs::write(nested.join("deep.txt"), b"ok").await.unwrap();
+
+        let entries = read_dir(&root, true).await.unwrap().collect::<Vec<_>>().await;
+        fs::remove_dir_all(&root).await.unwrap();
+
+        let mut names = entries
+            .into_iter()
+            .filter_map(Result::ok)
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+
+        assert_eq!(names, vec!["deep.txt".to_owned(), "top.txt".to_owned()]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn read_dir_reports_an_unreadable_subdir_without_dropping_siblings() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = temp_sub_dir("unreadable_subdir");
+        let blocked = root.join("blocked");
+        fs::create_dir_all(&blocked).await.unwrap();
+        fs::write(root.join("sibling.txt"), b"ok").await.unwrap();
+        fs::write(blocked.join("inner.txt"), b"ok").await.unwrap();
+        fs::set_permissions(&blocked, std::fs::Permissions::from_mode(0o000))
+            .await
+            .unwrap();
+
+        let entries = read_dir(&root, true).await.unwrap().collect::<Vec<_>>().await;
+
+        fs::set_permissions(&blocked, std::fs::Permissions::from_mode(0o755))
+            .await
+            .unwrap();
+        fs::remove_dir_all(&root).await.unwrap();
+
+        if !entries.iter().any(Result::is_err) {
+            // Running with elevated privileges bypasses the permission bits
+            // this test relies on (e.g. root); there's nothing to assert
+            // about the failure path in that case.
+            return;
+        }
+
+        assert!(entries
+            .iter()
+            .filter_map(|e| e.as_ref().ok())
+            .any(|e| e.file_name() == "sibling.txt"));
+    }
 }
@@ -3,18 +3,14 @@
 #![warn(clippy::nursery)]
 #![allow(clippy::missing_errors_doc)]
 
-use std::path::{Path, PathBuf};
+use std::fmt;
+use std::path::PathBuf;
 
 use anyhow::Result;
-use async_stream::try_stream;
 use clap::{AppSettings, Clap};
-use colored::Colorize;
-use crc32fast::Hasher;
-use futures_util::stream::{Stream, StreamExt, TryStreamExt};
-use tokio::{
-    fs::{self, DirEntry, File},
-    io::{AsyncReadExt, ErrorKind},
-};
+use colored::{ColoredString, Colorize};
+use crccheck_rs::{Algorithm, FileReport, Status};
+use indicatif::{ProgressBar, ProgressStyle};
 
 /// Simple CLI tool to check CRC values in file names
 #[derive(Debug, Clap)]
@@ -24,6 +20,28 @@ struct Opt {
     #[clap(short, long)]
     update: bool,
 
+    /// Whether to recurse into subdirectories
+    #[clap(short, long)]
+    recursive: bool,
+
+    /// The hash algorithm to look for and compute
+    #[clap(
+        short,
+        long,
+        arg_enum,
+        case_insensitive = true,
+        default_value = "Crc32"
+    )]
+    algo: Algorithm,
+
+    /// Verify against an SFV manifest instead of hashes embedded in file names
+    #[clap(long, parse(from_os_str))]
+    sfv: Option<PathBuf>,
+
+    /// When used with --sfv, add files missing from the manifest to it
+    #[clap(long, requires = "sfv")]
+    add: bool,
+
     /// The directory where to search for files
     #[clap(parse(from_os_str), default_value = ".")]
     dir: PathBuf,
@@ -32,111 +50,98 @@ struct Opt {
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<()> {
     let opt: Opt = Opt::parse();
-    check(opt.dir, opt.update).await
-}
-
-pub async fn check<P: AsRef<Path> + Send>(dir: P, update: bool) -> Result<()> {
-    let files = read_dir(dir).await?.collect::<Vec<_>>().await;
-
-    let temp = futures_util::stream::iter(files)
-        .try_filter_map(|file: DirEntry| async move {
-            if file.metadata().await?.is_file() {
-                Ok(Some(check_crc(file.path(), update)))
-            } else {
-                Ok(None)
-            }
-        })
-        .try_buffer_unordered(num_cpus::get() * 2);
-
-    Box::pin(temp).try_collect::<()>().await
-}
-
-async fn read_dir(dir: impl AsRef<Path> + Send) -> Result<impl Stream<Item = Result<DirEntry>>> {
-    let dir = dir.as_ref().to_owned();
-    let mut files = fs::read_dir(dir).await?;
-
-    Ok(try_stream! {
-        while let Some(entry) = files.next_entry().await? {
-            yield entry;
-        }
-    })
-}
-
-async fn check_crc(file: PathBuf, update: bool) -> Result<()> {
-    let name = file.file_name().unwrap().to_str().unwrap();
-    let hash_bytes = match extract_hash(name)? {
-        Some(v) => v,
-        None => return Ok(()),
-    };
-    let calc_bytes = match calculate_hash(&file).await {
-        Ok(v) => v,
-        Err(e) => return Err(e),
-    };
 
-    let result = if hash_bytes == calc_bytes {
-        "OK".green()
-    } else if update {
-        rename_file(&file, hash_bytes, calc_bytes).await?;
-        "UPDATED".yellow()
+    let reports = if let Some(sfv) = opt.sfv {
+        crccheck_rs::check_sfv(opt.dir, sfv, opt.update, opt.add, opt.algo).await?
     } else {
-        "MISMATCH".red()
+        let progress = ProgressBar::new(0);
+        progress.set_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.cyan} {pos}/{len} checked")
+                .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏ "),
+        );
+        progress.enable_steady_tick(80);
+
+        let reports = crccheck_rs::check(
+            opt.dir,
+            opt.update,
+            opt.recursive,
+            opt.algo,
+            |done, total| {
+                progress.set_length(total as u64);
+                progress.set_position(done as u64);
+            },
+        )
+        .await?;
+
+        progress.finish_and_clear();
+        reports
     };
 
-    println!("{:>8} - {}", result, name);
+    print_reports(reports);
     Ok(())
 }
 
-fn extract_hash(name: &str) -> Result<Option<u32>> {
-    let mut sub = &name[..];
-    while let Some((l, r)) = find_surrounded(sub, '[', ']') {
-        let hex = &sub[l + 1..r];
-        if is_u32_hex(hex) {
-            return Ok(Some(u32::from_str_radix(hex, 16)?));
-        }
-        sub = &sub[..l];
+/// Sort reports into natural/human order, print each with a colored status,
+/// then tally OK/MISMATCH/UPDATED/SKIPPED/MISSING/ADDED counts at the end.
+fn print_reports(mut reports: Vec<FileReport>) {
+    reports.sort_by(|a, b| natord::compare(&a.path.to_string_lossy(), &b.path.to_string_lossy()));
+
+    let mut tally = Tally::default();
+    for report in &reports {
+        tally.record(report.status);
+        println!(
+            "{:>8} - {}",
+            status_label(report.status),
+            report.path.display()
+        );
     }
-    Ok(None)
+    println!("{tally}");
 }
 
-#[inline]
-fn find_surrounded(text: &str, left: char, right: char) -> Option<(usize, usize)> {
-    if let Some(r) = text.rfind(right) {
-        if let Some(l) = text[..r].rfind(left) {
-            return Some((l, r));
-        }
+fn status_label(status: Status) -> ColoredString {
+    match status {
+        Status::Ok => "OK".green(),
+        Status::Mismatch => "MISMATCH".red(),
+        Status::Updated => "UPDATED".yellow(),
+        Status::Skipped => "SKIPPED".magenta(),
+        Status::Missing => "MISSING".red(),
+        Status::Added => "ADDED".blue(),
     }
-    None
 }
 
-#[inline]
-fn is_u32_hex(text: &str) -> bool {
-    text.len() == 8 && text.chars().all(|c| "0123456789abcdefABCDEF".contains(c))
+/// Running totals of each [`Status`] seen over a run.
+#[derive(Debug, Default)]
+struct Tally {
+    ok: usize,
+    mismatch: usize,
+    updated: usize,
+    skipped: usize,
+    missing: usize,
+    added: usize,
 }
 
-async fn calculate_hash(file: &PathBuf) -> Result<u32> {
-    let mut file = File::open(file).await?;
-    let mut buf = [0_u8; 8192];
-    let mut hasher = Hasher::new();
-
-    loop {
-        match file.read(&mut buf).await {
-            Ok(0) => return Ok(hasher.finalize()),
-            Ok(len) => hasher.update(&buf[..len]),
-            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
-            Err(e) => return Err(e.into()),
-        };
+impl Tally {
+    const fn record(&mut self, status: Status) {
+        match status {
+            Status::Ok => self.ok += 1,
+            Status::Mismatch => self.mismatch += 1,
+            Status::Updated => self.updated += 1,
+            Status::Skipped => self.skipped += 1,
+            Status::Missing => self.missing += 1,
+            Status::Added => self.added += 1,
+        }
     }
 }
 
-async fn rename_file(file: &PathBuf, hash_bytes: u32, calc_bytes: u32) -> Result<()> {
-    let crc_hash = format!("[{:08X}]", hash_bytes);
-    let crc_calc = format!("[{:08X}]", calc_bytes);
-    let new_name = file
-        .to_str()
-        .unwrap_or_default()
-        .replace(&crc_hash, &crc_calc);
-    fs::rename(file, new_name).await?;
-    Ok(())
+impl fmt::Display for Tally {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} OK, {} mismatch, {} updated, {} skipped, {} missing, {} added",
+            self.ok, self.mismatch, self.updated, self.skipped, self.missing, self.added
+        )
+    }
 }
 
 #[cfg(test)]
@@ -144,39 +149,55 @@ mod tests {
     use super::*;
 
     #[test]
-    fn extract_hash_works() {
-        let cases = [
-            ("[11111111]", "11111111"),
-            ("[aabbccdd]", "AABBCCDD"),
-            ("[11111111]aa[bbb].txt", "11111111"),
-            ("[11111111][22222222]", "22222222"),
-        ];
-
-        for (input, expect) in &cases {
-            let result = extract_hash(input);
-            if let Ok(Some(i)) = result {
-                assert_eq!(expect, &format!("{:08X}", i));
-            } else {
-                panic!("Expected {} but got {:?}", expect, result);
-            }
-        }
+    fn sorting_reports_orders_numeric_suffixes_naturally() {
+        let mut reports = vec!["file10.bin", "file2.bin", "file1.bin"]
+            .into_iter()
+            .map(|name| FileReport {
+                path: PathBuf::from(name),
+                expected: None,
+                actual: None,
+                status: Status::Ok,
+            })
+            .collect::<Vec<_>>();
+
+        reports
+            .sort_by(|a, b| natord::compare(&a.path.to_string_lossy(), &b.path.to_string_lossy()));
+
+        let names = reports
+            .iter()
+            .map(|r| r.path.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["file1.bin", "file2.bin", "file10.bin"]);
     }
 
     #[test]
-    fn extract_hash_fails() {
-        let cases = [
-            "[111]",
-            "[1111111122]",
-            "[aabbccdd",
-            "aabbccdd]",
-            "aabbccdd",
-        ];
-
-        for input in &cases {
-            let result = extract_hash(input);
-            if let Ok(Some(i)) = result {
-                panic!("No valued expected but got {}", format!("{:08X}", i));
-            }
-        }
+    fn tally_records_each_status_independently() {
+        let mut tally = Tally::default();
+        tally.record(Status::Ok);
+        tally.record(Status::Ok);
+        tally.record(Status::Mismatch);
+        tally.record(Status::Updated);
+        tally.record(Status::Skipped);
+        tally.record(Status::Missing);
+        tally.record(Status::Added);
+
+        assert_eq!(tally.ok, 2);
+        assert_eq!(tally.mismatch, 1);
+        assert_eq!(tally.updated, 1);
+        assert_eq!(tally.skipped, 1);
+        assert_eq!(tally.missing, 1);
+        assert_eq!(tally.added, 1);
+    }
+
+    #[test]
+    fn tally_display_matches_the_expected_format() {
+        let mut tally = Tally::default();
+        tally.record(Status::Ok);
+        tally.record(Status::Mismatch);
+
+        assert_eq!(
+            tally.to_string(),
+            "1 OK, 1 mismatch, 0 updated, 0 skipped, 0 missing, 0 added"
+        );
     }
 }
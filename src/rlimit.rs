@@ -0,0 +1,60 @@
+//! Raises the process's soft limit on open file descriptors before the
+//! pipeline fans out into many concurrent file opens, so large directories
+//! don't trip `EMFILE` on platforms with a low default (macOS, some BSDs).
+
+const OPEN_MAX: u64 = 65536;
+
+#[cfg(unix)]
+pub fn raise_nofile_limit() {
+    use rlimit::{Resource, Rlim};
+
+    if let Ok((_, hard)) = Resource::NOFILE.get() {
+        #[cfg(target_os = "macos")]
+        let hard = macos_max_files_per_proc().map_or(hard, |max| hard.min(Rlim::from_raw(max)));
+
+        let soft = Rlim::from_raw(OPEN_MAX).min(hard);
+        let _ = Resource::NOFILE.set(soft, hard);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn raise_nofile_limit() {}
+
+/// On macOS, `getrlimit` commonly reports the hard limit on open files as
+/// unlimited even though `setrlimit` still enforces the stricter
+/// `kern.maxfilesperproc` ceiling underneath it; raising past that secret
+/// limit makes the call fail outright, so it has to be looked up separately
+/// via sysctl and clamped to.
+#[cfg(target_os = "macos")]
+fn macos_max_files_per_proc() -> Option<u64> {
+    use std::convert::TryFrom;
+
+    use sysctl::Sysctl;
+
+    match sysctl::Ctl::new("kern.maxfilesperproc").ok()?.value().ok()? {
+        sysctl::CtlValue::Int(v) => u64::try_from(v).ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raise_nofile_limit_does_not_panic() {
+        raise_nofile_limit();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn raise_nofile_limit_caps_the_soft_limit_at_open_max_and_the_hard_limit() {
+        use rlimit::{Resource, Rlim};
+
+        raise_nofile_limit();
+
+        let (soft, hard) = Resource::NOFILE.get().unwrap();
+        assert!(soft <= Rlim::from_raw(OPEN_MAX));
+        assert!(soft <= hard);
+    }
+}
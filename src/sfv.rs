@@ -0,0 +1,115 @@
+//! Reading and writing of SFV-style checksum manifests.
+//!
+//! An SFV file lists one `filename checksum` pair per line, with lines
+//! starting with `;` treated as comments. This lets a checksum be verified
+//! or stored without touching the file name itself.
+
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+
+/// A single `filename checksum` entry of a manifest.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub name: String,
+    pub hash: Vec<u8>,
+}
+
+/// Parse a manifest at `path`, skipping blank lines and `;` comments.
+pub async fn parse(path: impl AsRef<Path>) -> Result<Vec<Entry>> {
+    let content = tokio::fs::read_to_string(path.as_ref()).await?;
+
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with(';'))
+        .map(|line| {
+            let (name, hash) = line
+                .rsplit_once(' ')
+                .ok_or_else(|| anyhow!("invalid manifest line: {line}"))?;
+            Ok(Entry {
+                name: name.to_owned(),
+                hash: hex::decode(hash)?,
+            })
+        })
+        .collect()
+}
+
+/// Write `entries` back out to `path`, one `filename checksum` pair per line.
+pub async fn write(path: impl AsRef<Path>, entries: &[Entry]) -> Result<()> {
+    let mut content = String::new();
+    for entry in entries {
+        content.push_str(&entry.name);
+        content.push(' ');
+        content.push_str(&hex::encode_upper(&entry.hash));
+        content.push('\n');
+    }
+
+    tokio::fs::write(path, content).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("crccheck_sfv_test_{}_{name}", std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn parse_skips_comments_and_blank_lines() {
+        let path = temp_path("skips");
+        tokio::fs::write(
+            &path,
+            "; this is a comment\n\nfile.bin AABBCCDD\n   \n; another comment\n",
+        )
+        .await
+        .unwrap();
+
+        let entries = parse(&path).await.unwrap();
+        tokio::fs::remove_file(&path).await.unwrap();
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "file.bin");
+        assert_eq!(entries[0].hash, vec![0xAA, 0xBB, 0xCC, 0xDD]);
+    }
+
+    #[tokio::test]
+    async fn parse_fails_on_malformed_line() {
+        let path = temp_path("malformed");
+        tokio::fs::write(&path, "this line has no checksum\n")
+            .await
+            .unwrap();
+
+        let result = parse(&path).await;
+        tokio::fs::remove_file(&path).await.unwrap();
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn write_then_parse_round_trips() {
+        let path = temp_path("roundtrip");
+        let entries = vec![
+            Entry {
+                name: "a.bin".to_owned(),
+                hash: vec![0x11, 0x22, 0x33, 0x44],
+            },
+            Entry {
+                name: "b.bin".to_owned(),
+                hash: vec![0xDE, 0xAD, 0xBE, 0xEF],
+            },
+        ];
+
+        write(&path, &entries).await.unwrap();
+        let parsed = parse(&path).await.unwrap();
+        tokio::fs::remove_file(&path).await.unwrap();
+
+        assert_eq!(parsed.len(), entries.len());
+        for (p, e) in parsed.iter().zip(&entries) {
+            assert_eq!(p.name, e.name);
+            assert_eq!(p.hash, e.hash);
+        }
+    }
+}